@@ -0,0 +1,295 @@
+// Copyright 2019 Joyent, Inc.
+
+//! Typed accessors over the free-form `headers` map attached to a
+//! `MantaObject`.
+//!
+//! Manta headers are stored as a raw `serde_json::Value` so the object can
+//! round-trip losslessly through SQLite, but most callers only ever want a
+//! handful of well-known headers coerced to a concrete type. `MantaHeaders`
+//! borrows the existing map and exposes schema-aware getters instead of
+//! making every caller re-parse the JSON scalars by hand.
+
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Str,
+    Long,
+    Double,
+    Bool,
+    Uuid,
+    Instant,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    /// The key was present but holds a JSON scalar that can't be coerced
+    /// to `expected`.
+    Mismatch { key: String, expected: ValueType },
+}
+
+impl std::fmt::Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TypeError::Mismatch { key, expected } => {
+                write!(f, "header '{}' is not a valid {:?}", key, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// A typed, read-only view over a `MantaObject`'s `headers` map.
+pub struct MantaHeaders<'a> {
+    headers: &'a Value,
+}
+
+impl<'a> MantaHeaders<'a> {
+    pub fn new(headers: &'a Value) -> Self {
+        MantaHeaders { headers }
+    }
+
+    pub fn get_str(&self, key: &str) -> Result<Option<&'a str>, TypeError> {
+        match self.headers.get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.as_str())),
+            Some(_) => Err(self.mismatch(key, ValueType::Str)),
+        }
+    }
+
+    pub fn get_long(&self, key: &str) -> Result<Option<i64>, TypeError> {
+        match self.headers.get(key) {
+            None => Ok(None),
+            Some(Value::Number(n)) if n.is_i64() => Ok(n.as_i64()),
+            Some(Value::String(s)) => s
+                .parse::<i64>()
+                .map(Some)
+                .map_err(|_| self.mismatch(key, ValueType::Long)),
+            Some(_) => Err(self.mismatch(key, ValueType::Long)),
+        }
+    }
+
+    pub fn get_double(&self, key: &str) -> Result<Option<f64>, TypeError> {
+        match self.headers.get(key) {
+            None => Ok(None),
+            Some(Value::Number(n)) => n
+                .as_f64()
+                .ok_or_else(|| self.mismatch(key, ValueType::Double))
+                .map(Some),
+            Some(_) => Err(self.mismatch(key, ValueType::Double)),
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, TypeError> {
+        match self.headers.get(key) {
+            None => Ok(None),
+            Some(Value::Bool(b)) => Ok(Some(*b)),
+            Some(_) => Err(self.mismatch(key, ValueType::Bool)),
+        }
+    }
+
+    pub fn get_uuid(&self, key: &str) -> Result<Option<Uuid>, TypeError> {
+        match self.headers.get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Uuid::parse_str(s)
+                .map(Some)
+                .map_err(|_| self.mismatch(key, ValueType::Uuid)),
+            Some(_) => Err(self.mismatch(key, ValueType::Uuid)),
+        }
+    }
+
+    pub fn get_instant(&self, key: &str) -> Result<Option<DateTime<Utc>>, TypeError> {
+        match self.headers.get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => DateTime::parse_from_rfc3339(s)
+                .map(|dt| Some(dt.with_timezone(&Utc)))
+                .map_err(|_| self.mismatch(key, ValueType::Instant)),
+            Some(_) => Err(self.mismatch(key, ValueType::Instant)),
+        }
+    }
+
+    fn mismatch(&self, key: &str, expected: ValueType) -> TypeError {
+        TypeError::Mismatch {
+            key: key.to_string(),
+            expected,
+        }
+    }
+}
+
+/// A value ready to be written back into a `headers` map via
+/// `MantaObject::insert_typed`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedValue {
+    Str(String),
+    Long(i64),
+    Double(f64),
+    Bool(bool),
+    Uuid(Uuid),
+    Instant(DateTime<Utc>),
+}
+
+impl From<TypedValue> for Value {
+    fn from(value: TypedValue) -> Self {
+        match value {
+            TypedValue::Str(s) => Value::String(s),
+            TypedValue::Long(n) => Value::from(n),
+            TypedValue::Double(d) => {
+                serde_json::Number::from_f64(d).map_or(Value::Null, Value::Number)
+            }
+            TypedValue::Bool(b) => Value::Bool(b),
+            TypedValue::Uuid(u) => Value::String(u.to_string()),
+            TypedValue::Instant(i) => Value::String(i.to_rfc3339()),
+        }
+    }
+}
+
+/// Insert `value` into `headers` under `key`, turning `headers` into a
+/// JSON object first if it wasn't one already (e.g. the default `Value`).
+pub(crate) fn insert_typed(headers: &mut Value, key: String, value: TypedValue) {
+    if !headers.is_object() {
+        *headers = Value::Object(Map::new());
+    }
+
+    if let Some(map) = headers.as_object_mut() {
+        map.insert(key, value.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn get_str_returns_the_string() {
+        let h = json!({"content-type": "text/plain"});
+        assert_eq!(
+            MantaHeaders::new(&h).get_str("content-type"),
+            Ok(Some("text/plain"))
+        );
+    }
+
+    #[test]
+    fn get_str_on_non_string_is_a_type_error() {
+        let h = json!({"m-flag": true});
+        assert_eq!(
+            MantaHeaders::new(&h).get_str("m-flag"),
+            Err(TypeError::Mismatch {
+                key: "m-flag".to_string(),
+                expected: ValueType::Str,
+            })
+        );
+    }
+
+    #[test]
+    fn get_long_coerces_numbers_and_numeric_strings() {
+        let h = json!({
+            "durability-level": 2,
+            "content-length": "1024",
+        });
+        let mh = MantaHeaders::new(&h);
+
+        assert_eq!(mh.get_long("durability-level"), Ok(Some(2)));
+        assert_eq!(mh.get_long("content-length"), Ok(Some(1024)));
+    }
+
+    #[test]
+    fn get_long_on_bool_is_a_type_error() {
+        let h = json!({"m-flag": true});
+        assert_eq!(
+            MantaHeaders::new(&h).get_long("m-flag"),
+            Err(TypeError::Mismatch {
+                key: "m-flag".to_string(),
+                expected: ValueType::Long,
+            })
+        );
+    }
+
+    #[test]
+    fn get_bool_returns_the_bool() {
+        let h = json!({"m-flag": false});
+        assert_eq!(MantaHeaders::new(&h).get_bool("m-flag"), Ok(Some(false)));
+    }
+
+    #[test]
+    fn get_uuid_parses_a_valid_uuid_string() {
+        let uuid = Uuid::new_v4();
+        let h = json!({"request-id": uuid.to_string()});
+        assert_eq!(
+            MantaHeaders::new(&h).get_uuid("request-id"),
+            Ok(Some(uuid))
+        );
+    }
+
+    #[test]
+    fn get_uuid_on_malformed_string_is_a_type_error() {
+        let h = json!({"request-id": "not-a-uuid"});
+        assert_eq!(
+            MantaHeaders::new(&h).get_uuid("request-id"),
+            Err(TypeError::Mismatch {
+                key: "request-id".to_string(),
+                expected: ValueType::Uuid,
+            })
+        );
+    }
+
+    #[test]
+    fn get_instant_parses_rfc3339() {
+        let h = json!({"last-modified": "2019-06-01T00:00:00Z"});
+        let instant = MantaHeaders::new(&h).get_instant("last-modified").unwrap();
+        assert_eq!(instant.unwrap().to_rfc3339(), "2019-06-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn get_instant_on_malformed_string_is_a_type_error() {
+        let h = json!({"last-modified": "not a date"});
+        assert_eq!(
+            MantaHeaders::new(&h).get_instant("last-modified"),
+            Err(TypeError::Mismatch {
+                key: "last-modified".to_string(),
+                expected: ValueType::Instant,
+            })
+        );
+    }
+
+    #[test]
+    fn missing_key_returns_none_for_every_getter() {
+        let h = json!({});
+        let mh = MantaHeaders::new(&h);
+
+        assert_eq!(mh.get_str("nope"), Ok(None));
+        assert_eq!(mh.get_long("nope"), Ok(None));
+        assert_eq!(mh.get_double("nope"), Ok(None));
+        assert_eq!(mh.get_bool("nope"), Ok(None));
+        assert_eq!(mh.get_uuid("nope"), Ok(None));
+        assert_eq!(mh.get_instant("nope"), Ok(None));
+    }
+
+    #[test]
+    fn insert_typed_writes_back_into_a_null_headers_value() {
+        let mut h = Value::Null;
+        insert_typed(&mut h, "m-custom".to_string(), TypedValue::Long(7));
+
+        assert_eq!(
+            MantaHeaders::new(&h).get_long("m-custom"),
+            Ok(Some(7))
+        );
+    }
+
+    #[test]
+    fn insert_typed_adds_a_key_to_an_existing_object() {
+        let mut h = json!({"existing": "value"});
+        insert_typed(
+            &mut h,
+            "m-flag".to_string(),
+            TypedValue::Bool(true),
+        );
+
+        let mh = MantaHeaders::new(&h);
+        assert_eq!(mh.get_str("existing"), Ok(Some("value")));
+        assert_eq!(mh.get_bool("m-flag"), Ok(Some(true)));
+    }
+}
@@ -0,0 +1,13 @@
+// Copyright 2019 Joyent, Inc.
+
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
+
+pub mod headers;
+pub mod migration;
+pub mod moray;
+pub mod query;
+pub mod store;
+pub mod util;
@@ -0,0 +1,123 @@
+// Copyright 2019 Joyent, Inc.
+
+//! Schema versioning for the JSON blob a `MantaObject` is serialized into.
+//!
+//! Because a `MantaObject` is stored as an opaque JSON string, any future
+//! field addition or rename would otherwise silently break deserialization
+//! of rows written by an older version of this crate. Every blob written by
+//! `ToSql` is stamped with a `_schema_version` field; `FromSql` reads that
+//! field back and runs the payload through whichever `migrate_vN_to_vN+1`
+//! steps are needed to bring it up to `CURRENT_SCHEMA_VERSION` before
+//! handing it to `serde_json::from_value`.
+
+use serde_json::Value;
+use std::fmt;
+
+pub const SCHEMA_VERSION_FIELD: &str = "_schema_version";
+pub const CURRENT_SCHEMA_VERSION: u64 = 1;
+
+/// A `_schema_version` this crate doesn't know how to migrate, e.g. a row
+/// written by a newer version of the crate than is currently loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownSchemaVersion {
+    pub found: u64,
+}
+
+impl fmt::Display for UnknownSchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "don't know how to migrate schema version {} (current is {})",
+            self.found, CURRENT_SCHEMA_VERSION
+        )
+    }
+}
+
+impl std::error::Error for UnknownSchemaVersion {}
+
+/// Stamp `value` (expected to be a JSON object) with the current schema
+/// version.
+pub fn stamp_current_version(mut value: Value) -> Value {
+    if let Some(map) = value.as_object_mut() {
+        map.insert(
+            SCHEMA_VERSION_FIELD.to_string(),
+            Value::from(CURRENT_SCHEMA_VERSION),
+        );
+    }
+    value
+}
+
+/// Read the embedded `_schema_version`, defaulting to `0` for payloads
+/// written before this field existed.
+fn schema_version(value: &Value) -> u64 {
+    value
+        .get(SCHEMA_VERSION_FIELD)
+        .and_then(Value::as_u64)
+        .unwrap_or(0)
+}
+
+/// Payloads written before `_schema_version` existed. The struct shape
+/// hasn't changed since, so this step only adds the field.
+fn migrate_v0_to_v1(value: Value) -> Value {
+    stamp_current_version(value)
+}
+
+/// Run `value` through whichever `migrate_vN_to_vN+1` steps are needed to
+/// bring it up to `CURRENT_SCHEMA_VERSION`. Fails if `value` carries a
+/// version newer than this crate knows how to migrate, rather than
+/// silently handing an un-migrated payload to serde.
+pub fn migrate_to_current(value: Value) -> Result<Value, UnknownSchemaVersion> {
+    let mut value = value;
+    let mut version = schema_version(&value);
+
+    if version == 0 {
+        value = migrate_v0_to_v1(value);
+        version = 1;
+    }
+
+    if version != CURRENT_SCHEMA_VERSION {
+        return Err(UnknownSchemaVersion { found: version });
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn missing_schema_version_is_treated_as_v0() {
+        let fixture = json!({"key": "foo"});
+        assert_eq!(schema_version(&fixture), 0);
+    }
+
+    #[test]
+    fn v0_payload_migrates_to_current() {
+        let fixture = json!({"key": "foo"});
+        let migrated = migrate_to_current(fixture).unwrap();
+        assert_eq!(schema_version(&migrated), CURRENT_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn future_schema_version_fails_loudly_instead_of_being_silently_accepted() {
+        let fixture =
+            json!({"key": "foo", "_schema_version": CURRENT_SCHEMA_VERSION + 1});
+        assert_eq!(
+            migrate_to_current(fixture),
+            Err(UnknownSchemaVersion {
+                found: CURRENT_SCHEMA_VERSION + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn stamp_current_version_sets_the_field() {
+        let value = stamp_current_version(json!({}));
+        assert_eq!(
+            value.get(SCHEMA_VERSION_FIELD).and_then(Value::as_u64),
+            Some(CURRENT_SCHEMA_VERSION)
+        );
+    }
+}
@@ -1,5 +1,7 @@
 // Copyright 2019 Joyent, Inc.
 
+use crate::headers::{self, MantaHeaders, TypedValue};
+use crate::migration;
 use crate::util;
 use base64;
 use diesel::backend;
@@ -15,8 +17,17 @@ use serde_json::{Map, Value};
 use std::io::Write;
 use uuid::Uuid;
 
-#[derive(Deserialize, Serialize, PartialEq, Debug, Clone)]
+#[derive(
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Debug,
+    Clone,
+    FromSqlRow,
+    AsExpression,
+)]
 #[serde(tag = "type")]
+#[sql_type = "sql_types::Text"]
 pub enum ObjectType {
     #[serde(alias = "object")]
     Object(MantaObject),
@@ -25,6 +36,32 @@ pub enum ObjectType {
     Directory(MantaDirectory),
 }
 
+impl ToSql<sql_types::Text, Sqlite> for ObjectType {
+    fn to_sql<W: Write>(
+        &self,
+        out: &mut Output<W, Sqlite>,
+    ) -> serialize::Result {
+        let value = migration::stamp_current_version(
+            serde_json::to_value(&self).unwrap(),
+        );
+        let obj_type_str = serde_json::to_string(&value).unwrap();
+        out.write_all(obj_type_str.as_bytes())?;
+
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<sql_types::Text, Sqlite> for ObjectType {
+    fn from_sql(
+        bytes: Option<backend::RawValue<Sqlite>>,
+    ) -> deserialize::Result<Self> {
+        let value: Value = serde_json::from_str(not_none!(bytes).read_text())?;
+        let obj_type: ObjectType =
+            serde_json::from_value(migration::migrate_to_current(value)?)?;
+        Ok(obj_type)
+    }
+}
+
 #[derive(
     Deserialize,
     Serialize,
@@ -70,12 +107,30 @@ pub struct MantaObject {
     pub obj_type: String,
 }
 
+impl MantaObject {
+    /// Borrow this object's `headers` map as a typed, schema-aware view
+    /// without cloning the underlying `Value`.
+    pub fn typed_headers(&self) -> MantaHeaders<'_> {
+        MantaHeaders::new(&self.headers)
+    }
+
+    /// Write `value` into `headers` under `key`, coercing it to the
+    /// matching JSON scalar. This preserves the raw, lossless map used
+    /// for round-tripping through SQLite.
+    pub fn insert_typed<S: Into<String>>(&mut self, key: S, value: TypedValue) {
+        headers::insert_typed(&mut self.headers, key.into(), value);
+    }
+}
+
 impl ToSql<sql_types::Text, Sqlite> for MantaObject {
     fn to_sql<W: Write>(
         &self,
         out: &mut Output<W, Sqlite>,
     ) -> serialize::Result {
-        let manta_str = serde_json::to_string(&self).unwrap();
+        let value = migration::stamp_current_version(
+            serde_json::to_value(&self).unwrap(),
+        );
+        let manta_str = serde_json::to_string(&value).unwrap();
         out.write_all(manta_str.as_bytes())?;
 
         Ok(IsNull::No)
@@ -86,8 +141,9 @@ impl FromSql<sql_types::Text, Sqlite> for MantaObject {
     fn from_sql(
         bytes: Option<backend::RawValue<Sqlite>>,
     ) -> deserialize::Result<Self> {
+        let value: Value = serde_json::from_str(not_none!(bytes).read_text())?;
         let manta_obj: MantaObject =
-            serde_json::from_str(not_none!(bytes).read_text())?;
+            serde_json::from_value(migration::migrate_to_current(value)?)?;
         Ok(manta_obj)
     }
 }
@@ -98,7 +154,17 @@ pub struct MantaObjectShark {
     pub manta_storage_id: String,
 }
 
-#[derive(Deserialize, Default, Serialize, PartialEq, Debug, Clone)]
+#[derive(
+    Deserialize,
+    Default,
+    Serialize,
+    PartialEq,
+    Debug,
+    Clone,
+    FromSqlRow,
+    AsExpression,
+)]
+#[sql_type = "sql_types::Text"]
 pub struct MantaDirectory {
     pub creator: String,
     pub dirname: String,
@@ -111,6 +177,28 @@ pub struct MantaDirectory {
     pub vnode: u64,
 }
 
+impl ToSql<sql_types::Text, Sqlite> for MantaDirectory {
+    fn to_sql<W: Write>(
+        &self,
+        out: &mut Output<W, Sqlite>,
+    ) -> serialize::Result {
+        let manta_dir_str = serde_json::to_string(&self).unwrap();
+        out.write_all(manta_dir_str.as_bytes())?;
+
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<sql_types::Text, Sqlite> for MantaDirectory {
+    fn from_sql(
+        bytes: Option<backend::RawValue<Sqlite>>,
+    ) -> deserialize::Result<Self> {
+        let manta_dir: MantaDirectory =
+            serde_json::from_str(not_none!(bytes).read_text())?;
+        Ok(manta_dir)
+    }
+}
+
 // Implement Arbitrary traits for testing
 impl Arbitrary for MantaObjectShark {
     fn arbitrary<G: Gen>(g: &mut G) -> MantaObjectShark {
@@ -192,13 +280,209 @@ impl Arbitrary for MantaObject {
     }
 }
 
+impl Arbitrary for MantaDirectory {
+    fn arbitrary<G: Gen>(g: &mut G) -> MantaDirectory {
+        let len = g.gen::<u8>() as usize;
+
+        let mut headers_map = Map::new();
+        headers_map.insert(
+            util::random_string(g, len),
+            Value::String(util::random_string(g, len)),
+        );
+
+        MantaDirectory {
+            creator: util::random_string(g, len),
+            dirname: util::random_string(g, len),
+            headers: Value::Object(headers_map),
+            key: util::random_string(g, len),
+            mtime: g.gen(),
+            name: util::random_string(g, len),
+            owner: Uuid::new_v4().to_string(),
+            roles: vec![util::random_string(g, len)],
+            vnode: g.gen(),
+        }
+    }
+}
+
+impl Arbitrary for ObjectType {
+    fn arbitrary<G: Gen>(g: &mut G) -> ObjectType {
+        if g.gen::<bool>() {
+            ObjectType::Object(MantaObject::arbitrary(g))
+        } else {
+            ObjectType::Directory(MantaDirectory::arbitrary(g))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use quickcheck::quickcheck;
     use regex::Regex;
+    use serde_json::json;
+    use serde_test::{assert_tokens, Token};
     use std::str::FromStr;
 
+    #[test]
+    fn manta_object_serde_round_trip() {
+        // Locks down `MantaObject`'s own serde shape at the token level.
+        // `_schema_version` is stamped onto the JSON `Value` by
+        // `ToSql`/`FromSql` (see `migration::stamp_current_version`), not
+        // part of this derive, so it doesn't appear below; this test's
+        // job is to fail loudly the moment a field is added, renamed, or
+        // reordered without the corresponding `migrate_vN_to_vN+1` step
+        // being written, which is what actually protects old rows.
+        let obj = MantaObject {
+            headers: Value::Object(Map::new()),
+            key: "key".to_string(),
+            mtime: 1,
+            name: "name".to_string(),
+            creator: "creator".to_string(),
+            dirname: "dirname".to_string(),
+            owner: "owner".to_string(),
+            roles: vec![],
+            vnode: 1,
+            content_length: 0,
+            content_md5: String::new(),
+            content_type: String::new(),
+            object_id: String::new(),
+            etag: String::new(),
+            sharks: vec![],
+            obj_type: String::new(),
+        };
+
+        assert_tokens(
+            &obj,
+            &[
+                Token::Struct {
+                    name: "MantaObject",
+                    len: 16,
+                },
+                Token::Str("headers"),
+                Token::Map { len: Some(0) },
+                Token::MapEnd,
+                Token::Str("key"),
+                Token::Str("key"),
+                Token::Str("mtime"),
+                Token::U64(1),
+                Token::Str("name"),
+                Token::Str("name"),
+                Token::Str("creator"),
+                Token::Str("creator"),
+                Token::Str("dirname"),
+                Token::Str("dirname"),
+                Token::Str("owner"),
+                Token::Str("owner"),
+                Token::Str("roles"),
+                Token::Seq { len: Some(0) },
+                Token::SeqEnd,
+                Token::Str("vnode"),
+                Token::U64(1),
+                Token::Str("contentLength"),
+                Token::U64(0),
+                Token::Str("contentMd5"),
+                Token::Str(""),
+                Token::Str("contentType"),
+                Token::Str(""),
+                Token::Str("objectId"),
+                Token::Str(""),
+                Token::Str("etag"),
+                Token::Str(""),
+                Token::Str("sharks"),
+                Token::Seq { len: Some(0) },
+                Token::SeqEnd,
+                Token::Str("objType"),
+                Token::Str(""),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn legacy_payload_without_schema_version_upgrades() {
+        // A `MantaObject` as it would have been written before
+        // `_schema_version` existed.
+        let legacy = json!({
+            "headers": {},
+            "key": "/poseidon/stor/foo",
+            "mtime": 123_456_789,
+            "name": "foo",
+            "creator": "poseidon",
+            "dirname": "/poseidon/stor",
+            "owner": "poseidon",
+            "roles": [],
+            "vnode": 1,
+            "contentLength": 42,
+            "contentMD5": "deadbeef",
+            "contentType": "text/plain",
+            "objectId": "00000000-0000-0000-0000-000000000000",
+            "etag": "11111111-1111-1111-1111-111111111111",
+            "sharks": [],
+            "type": "object"
+        });
+
+        let migrated = migration::migrate_to_current(legacy).unwrap();
+        let obj: MantaObject = serde_json::from_value(migrated).unwrap();
+
+        assert_eq!(obj.name, "foo");
+        assert_eq!(obj.content_length, 42);
+        assert_eq!(obj.content_type, "text/plain");
+    }
+
+    #[test]
+    fn manta_object_round_trips_through_to_sql_and_from_sql_with_schema_version() {
+        use diesel::prelude::*;
+        use diesel::sqlite::SqliteConnection;
+
+        table! {
+            manta_object_round_trip_probe (id) {
+                id -> Integer,
+                blob -> Text,
+            }
+        }
+
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE manta_object_round_trip_probe (
+                 id INTEGER NOT NULL,
+                 blob TEXT NOT NULL
+             )",
+        )
+        .unwrap();
+
+        let mut gen = quickcheck::StdGen::new(rand::thread_rng(), 16);
+        let obj = MantaObject::arbitrary(&mut gen);
+
+        // Insert drives the real `ToSql` impl; `blob` is typed as
+        // `sql_types::Text` and `MantaObject` derives `AsExpression` for
+        // it, so this is the same code path a real caller hits.
+        diesel::insert_into(manta_object_round_trip_probe::table)
+            .values((
+                manta_object_round_trip_probe::id.eq(1),
+                manta_object_round_trip_probe::blob.eq(obj.clone()),
+            ))
+            .execute(&conn)
+            .unwrap();
+
+        let raw: String = manta_object_round_trip_probe::table
+            .select(manta_object_round_trip_probe::blob)
+            .first(&conn)
+            .unwrap();
+        assert!(raw.contains(&format!(
+            "\"{}\":{}",
+            migration::SCHEMA_VERSION_FIELD,
+            migration::CURRENT_SCHEMA_VERSION
+        )));
+
+        // Select drives the real `FromSql` impl, including the
+        // migration step.
+        let round_tripped: MantaObject = manta_object_round_trip_probe::table
+            .select(manta_object_round_trip_probe::blob)
+            .first(&conn)
+            .unwrap();
+        assert_eq!(round_tripped, obj);
+    }
+
     quickcheck!(
         fn create_manta_object(mobj: MantaObject) -> bool {
             dbg!(&mobj);
@@ -224,4 +508,109 @@ mod tests {
             true
         }
     );
+
+    quickcheck!(
+        fn object_type_round_trips_through_json(obj_type: ObjectType) -> bool {
+            let serialized = serde_json::to_string(&obj_type).unwrap();
+            let deserialized: ObjectType =
+                serde_json::from_str(&serialized).unwrap();
+
+            deserialized == obj_type
+        }
+    );
+
+    #[test]
+    fn object_type_and_manta_directory_round_trip_through_sqlite() {
+        use diesel::prelude::*;
+        use diesel::sqlite::SqliteConnection;
+
+        table! {
+            object_type_round_trip_probe (id) {
+                id -> Integer,
+                blob -> Text,
+            }
+        }
+
+        table! {
+            manta_directory_round_trip_probe (id) {
+                id -> Integer,
+                blob -> Text,
+            }
+        }
+
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE object_type_round_trip_probe (
+                 id INTEGER NOT NULL,
+                 blob TEXT NOT NULL
+             )",
+        )
+        .unwrap();
+        conn.execute(
+            "CREATE TABLE manta_directory_round_trip_probe (
+                 id INTEGER NOT NULL,
+                 blob TEXT NOT NULL
+             )",
+        )
+        .unwrap();
+
+        let mut gen = quickcheck::StdGen::new(rand::thread_rng(), 16);
+
+        let obj_type = ObjectType::Directory(MantaDirectory::arbitrary(&mut gen));
+        diesel::insert_into(object_type_round_trip_probe::table)
+            .values((
+                object_type_round_trip_probe::id.eq(1),
+                object_type_round_trip_probe::blob.eq(obj_type.clone()),
+            ))
+            .execute(&conn)
+            .unwrap();
+
+        let round_tripped_type: ObjectType = object_type_round_trip_probe::table
+            .select(object_type_round_trip_probe::blob)
+            .first(&conn)
+            .unwrap();
+        assert_eq!(round_tripped_type, obj_type);
+
+        let object_variant = ObjectType::Object(MantaObject::arbitrary(&mut gen));
+        diesel::insert_into(object_type_round_trip_probe::table)
+            .values((
+                object_type_round_trip_probe::id.eq(2),
+                object_type_round_trip_probe::blob.eq(object_variant.clone()),
+            ))
+            .execute(&conn)
+            .unwrap();
+
+        let raw: String = object_type_round_trip_probe::table
+            .filter(object_type_round_trip_probe::id.eq(2))
+            .select(object_type_round_trip_probe::blob)
+            .first(&conn)
+            .unwrap();
+        assert!(raw.contains(&format!(
+            "\"{}\":{}",
+            migration::SCHEMA_VERSION_FIELD,
+            migration::CURRENT_SCHEMA_VERSION
+        )));
+
+        let round_tripped_object: ObjectType = object_type_round_trip_probe::table
+            .filter(object_type_round_trip_probe::id.eq(2))
+            .select(object_type_round_trip_probe::blob)
+            .first(&conn)
+            .unwrap();
+        assert_eq!(round_tripped_object, object_variant);
+
+        let directory = MantaDirectory::arbitrary(&mut gen);
+        diesel::insert_into(manta_directory_round_trip_probe::table)
+            .values((
+                manta_directory_round_trip_probe::id.eq(1),
+                manta_directory_round_trip_probe::blob.eq(directory.clone()),
+            ))
+            .execute(&conn)
+            .unwrap();
+
+        let round_tripped_dir: MantaDirectory = manta_directory_round_trip_probe::table
+            .select(manta_directory_round_trip_probe::blob)
+            .first(&conn)
+            .unwrap();
+        assert_eq!(round_tripped_dir, directory);
+    }
 }
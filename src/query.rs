@@ -0,0 +1,617 @@
+// Copyright 2019 Joyent, Inc.
+
+//! A small Moray-style filter DSL for querying collections of
+//! `MantaObject`s, mirroring the `findobjects` filter syntax used against
+//! Manta metadata, e.g.:
+//!
+//! ```text
+//! (content_type == "text/plain") AND (headers.durability-level >= 2)
+//! ```
+//!
+//! The pipeline is the usual lexer -> recursive-descent parser -> AST
+//! evaluator. `filter()` is the convenience entry point most callers want.
+
+use crate::moray::MantaObject;
+use serde_json::Value;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+// ---------------------------------------------------------------------
+// Lexer
+// ---------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Field(String),
+    Str(String),
+    Int(i64),
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Glob,
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    UnexpectedChar(char),
+    UnterminatedString,
+    UnexpectedToken(String),
+    UnexpectedEof,
+    UnknownField(String),
+    TypeMismatch { field: String, op: Op },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::UnexpectedChar(c) => write!(f, "unexpected character '{}'", c),
+            QueryError::UnterminatedString => write!(f, "unterminated string literal"),
+            QueryError::UnexpectedToken(t) => write!(f, "unexpected token '{}'", t),
+            QueryError::UnexpectedEof => write!(f, "unexpected end of filter"),
+            QueryError::UnknownField(field) => write!(f, "unknown field '{}'", field),
+            QueryError::TypeMismatch { field, op } => {
+                write!(f, "type mismatch comparing '{}' with {:?}", field, op)
+            }
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+pub struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Lexer {
+            chars: input.chars().peekable(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some(c) if c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn read_while<F: Fn(char) -> bool>(&mut self, pred: F) -> String {
+        let mut s = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if pred(c) {
+                s.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        s
+    }
+
+    pub fn tokenize(mut self) -> Result<Vec<Token>, QueryError> {
+        let mut tokens = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            let c = match self.chars.peek() {
+                None => break,
+                Some(&c) => c,
+            };
+
+            let token = match c {
+                '(' => {
+                    self.chars.next();
+                    Token::LParen
+                }
+                ')' => {
+                    self.chars.next();
+                    Token::RParen
+                }
+                '=' => {
+                    self.chars.next();
+                    self.expect('=')?;
+                    Token::Eq
+                }
+                '!' => {
+                    self.chars.next();
+                    self.expect('=')?;
+                    Token::Ne
+                }
+                '<' => {
+                    self.chars.next();
+                    if self.chars.peek() == Some(&'=') {
+                        self.chars.next();
+                        Token::Le
+                    } else {
+                        Token::Lt
+                    }
+                }
+                '>' => {
+                    self.chars.next();
+                    if self.chars.peek() == Some(&'=') {
+                        self.chars.next();
+                        Token::Ge
+                    } else {
+                        Token::Gt
+                    }
+                }
+                '~' => {
+                    self.chars.next();
+                    Token::Glob
+                }
+                '"' => {
+                    self.chars.next();
+                    let s = self.read_while(|c| c != '"');
+                    if self.chars.next() != Some('"') {
+                        return Err(QueryError::UnterminatedString);
+                    }
+                    Token::Str(s)
+                }
+                c if c.is_ascii_digit()
+                    || (c == '-' && self.is_negative_number_start()) =>
+                {
+                    let s = self.read_while(|c| c.is_ascii_digit() || c == '-');
+                    let n = s
+                        .parse::<i64>()
+                        .map_err(|_| QueryError::UnexpectedToken(s.clone()))?;
+                    Token::Int(n)
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    let s = self.read_while(|c| {
+                        c.is_alphanumeric() || c == '_' || c == '.' || c == '-'
+                    });
+                    match s.as_str() {
+                        "AND" => Token::And,
+                        "OR" => Token::Or,
+                        "NOT" => Token::Not,
+                        "LIKE" => Token::Glob,
+                        _ => Token::Field(s),
+                    }
+                }
+                c => return Err(QueryError::UnexpectedChar(c)),
+            };
+
+            tokens.push(token);
+        }
+
+        Ok(tokens)
+    }
+
+    fn is_negative_number_start(&mut self) -> bool {
+        let mut clone = self.chars.clone();
+        clone.next();
+        matches!(clone.peek(), Some(c) if c.is_ascii_digit())
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), QueryError> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(QueryError::UnexpectedChar(c)),
+            None => Err(QueryError::UnexpectedEof),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// AST
+// ---------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Glob,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Str(String),
+    Int(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Cmp {
+        field: String,
+        op: Op,
+        value: Literal,
+    },
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+// ---------------------------------------------------------------------
+// Parser (recursive descent, lowest to highest precedence: OR, AND, NOT)
+// ---------------------------------------------------------------------
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Parser { tokens, pos: 0 }
+    }
+
+    pub fn parse(input: &str) -> Result<Expr, QueryError> {
+        let tokens = Lexer::new(input).tokenize()?;
+        let mut parser = Parser::new(tokens);
+        let expr = parser.parse_or()?;
+        if parser.pos != parser.tokens.len() {
+            return Err(QueryError::UnexpectedToken(format!(
+                "{:?}",
+                parser.tokens[parser.pos]
+            )));
+        }
+        Ok(expr)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        if t.is_some() {
+            self.pos += 1;
+        }
+        t
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), QueryError> {
+        match self.next() {
+            Some(ref t) if t == token => Ok(()),
+            Some(t) => Err(QueryError::UnexpectedToken(format!("{:?}", t))),
+            None => Err(QueryError::UnexpectedEof),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut lhs = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            let rhs = self.parse_and()?;
+            lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut lhs = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            let rhs = self.parse_not()?;
+            lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, QueryError> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            let inner = self.parse_not()?;
+            return Ok(Expr::Not(Box::new(inner)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, QueryError> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                self.expect(&Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Field(field)) => {
+                let op = match self.next() {
+                    Some(Token::Eq) => Op::Eq,
+                    Some(Token::Ne) => Op::Ne,
+                    Some(Token::Lt) => Op::Lt,
+                    Some(Token::Le) => Op::Le,
+                    Some(Token::Gt) => Op::Gt,
+                    Some(Token::Ge) => Op::Ge,
+                    Some(Token::Glob) => Op::Glob,
+                    Some(t) => {
+                        return Err(QueryError::UnexpectedToken(format!("{:?}", t)))
+                    }
+                    None => return Err(QueryError::UnexpectedEof),
+                };
+                let value = match self.next() {
+                    Some(Token::Str(s)) => Literal::Str(s),
+                    Some(Token::Int(n)) => Literal::Int(n),
+                    Some(t) => {
+                        return Err(QueryError::UnexpectedToken(format!("{:?}", t)))
+                    }
+                    None => return Err(QueryError::UnexpectedEof),
+                };
+                Ok(Expr::Cmp { field, op, value })
+            }
+            Some(t) => Err(QueryError::UnexpectedToken(format!("{:?}", t))),
+            None => Err(QueryError::UnexpectedEof),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// Evaluator
+// ---------------------------------------------------------------------
+
+/// A resolved field value: either a known struct field or a `headers.*`
+/// lookup, normalized to a small set of comparable scalar types.
+enum Resolved {
+    Str(String),
+    Int(i64),
+    Missing,
+}
+
+impl Expr {
+    pub fn matches(&self, obj: &MantaObject) -> Result<bool, QueryError> {
+        match self {
+            Expr::Cmp { field, op, value } => eval_cmp(obj, field, *op, value),
+            Expr::And(lhs, rhs) => Ok(lhs.matches(obj)? && rhs.matches(obj)?),
+            Expr::Or(lhs, rhs) => Ok(lhs.matches(obj)? || rhs.matches(obj)?),
+            Expr::Not(inner) => Ok(!inner.matches(obj)?),
+        }
+    }
+}
+
+fn resolve_field(obj: &MantaObject, field: &str) -> Result<Resolved, QueryError> {
+    if let Some(header_key) = field.strip_prefix("headers.") {
+        return Ok(match obj.headers.get(header_key) {
+            None => Resolved::Missing,
+            Some(Value::String(s)) => Resolved::Str(s.clone()),
+            Some(Value::Number(n)) if n.is_i64() => Resolved::Int(n.as_i64().unwrap()),
+            Some(Value::Bool(b)) => Resolved::Str(b.to_string()),
+            Some(_) => Resolved::Missing,
+        });
+    }
+
+    Ok(match field {
+        "owner" => Resolved::Str(obj.owner.clone()),
+        "name" => Resolved::Str(obj.name.clone()),
+        "key" => Resolved::Str(obj.key.clone()),
+        "dirname" => Resolved::Str(obj.dirname.clone()),
+        "creator" => Resolved::Str(obj.creator.clone()),
+        "object_id" => Resolved::Str(obj.object_id.clone()),
+        "content_type" => Resolved::Str(obj.content_type.clone()),
+        "content_md5" => Resolved::Str(obj.content_md5.clone()),
+        "etag" => Resolved::Str(obj.etag.clone()),
+        "content_length" => Resolved::Int(obj.content_length as i64),
+        "mtime" => Resolved::Int(obj.mtime as i64),
+        "vnode" => Resolved::Int(obj.vnode as i64),
+        _ => return Err(QueryError::UnknownField(field.to_string())),
+    })
+}
+
+fn eval_cmp(
+    obj: &MantaObject,
+    field: &str,
+    op: Op,
+    value: &Literal,
+) -> Result<bool, QueryError> {
+    let mismatch = || QueryError::TypeMismatch {
+        field: field.to_string(),
+        op,
+    };
+
+    match resolve_field(obj, field)? {
+        Resolved::Missing => Ok(false),
+        Resolved::Str(s) => match (op, value) {
+            (Op::Glob, Literal::Str(pattern)) => Ok(glob_match(pattern, &s)),
+            (_, Literal::Str(v)) => Ok(compare(&s, v, op)),
+            _ => Err(mismatch()),
+        },
+        Resolved::Int(n) => match value {
+            Literal::Int(v) if op != Op::Glob => Ok(compare(&n, v, op)),
+            _ => Err(mismatch()),
+        },
+    }
+}
+
+fn compare<T: PartialOrd + PartialEq>(lhs: &T, rhs: &T, op: Op) -> bool {
+    match op {
+        Op::Eq => lhs == rhs,
+        Op::Ne => lhs != rhs,
+        Op::Lt => lhs < rhs,
+        Op::Le => lhs <= rhs,
+        Op::Gt => lhs > rhs,
+        Op::Ge => lhs >= rhs,
+        Op::Glob => false,
+    }
+}
+
+/// Minimal glob matcher supporting `*` as a multi-character wildcard.
+///
+/// Iterative two-pointer backtracking (the standard wildcard-matching
+/// algorithm): on a mismatch we rewind to the most recent `*` and retry
+/// one character further into `text` instead of recursing, so the work is
+/// bounded by the product of the lengths of `pattern` and `text` instead
+/// of blowing up exponentially on adversarial patterns like `"a*a*a*a*a*b"`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<usize> = None;
+    let mut star_match = 0;
+
+    while ti < text.len() {
+        if pi < pattern.len() && pattern[pi] == b'*' {
+            star = Some(pi);
+            star_match = ti;
+            pi += 1;
+        } else if pi < pattern.len() && pattern[pi] == text[ti] {
+            pi += 1;
+            ti += 1;
+        } else if let Some(star_pi) = star {
+            star_match += 1;
+            pi = star_pi + 1;
+            ti = star_match;
+        } else {
+            return false;
+        }
+    }
+
+    while pattern.get(pi) == Some(&b'*') {
+        pi += 1;
+    }
+
+    pi == pattern.len()
+}
+
+/// Parse `filter_str` and keep only the objects in `objs` that match it.
+pub fn filter(
+    objs: Vec<MantaObject>,
+    filter_str: &str,
+) -> Result<Vec<MantaObject>, QueryError> {
+    let expr = Parser::parse(filter_str)?;
+    objs.into_iter()
+        .map(|obj| expr.matches(&obj).map(|m| (m, obj)))
+        .collect::<Result<Vec<_>, _>>()
+        .map(|matched| {
+            matched
+                .into_iter()
+                .filter_map(|(m, obj)| if m { Some(obj) } else { None })
+                .collect()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn test_object() -> MantaObject {
+        MantaObject {
+            owner: "poseidon".to_string(),
+            content_type: "text/plain".to_string(),
+            content_length: 42,
+            mtime: 1_000,
+            headers: json!({
+                "durability-level": 2,
+                "m-custom": "hello",
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn eval(filter_str: &str, obj: &MantaObject) -> Result<bool, QueryError> {
+        Parser::parse(filter_str)?.matches(obj)
+    }
+
+    #[test]
+    fn unknown_top_level_field_is_a_hard_error() {
+        let obj = test_object();
+        assert_eq!(
+            eval("bogus_field == \"x\"", &obj),
+            Err(QueryError::UnknownField("bogus_field".to_string()))
+        );
+    }
+
+    #[test]
+    fn numeric_field_compared_to_string_literal_is_a_type_error() {
+        let obj = test_object();
+        assert_eq!(
+            eval("content_length == \"42\"", &obj),
+            Err(QueryError::TypeMismatch {
+                field: "content_length".to_string(),
+                op: Op::Eq,
+            })
+        );
+    }
+
+    #[test]
+    fn string_field_compared_to_integer_literal_is_a_type_error() {
+        let obj = test_object();
+        assert_eq!(
+            eval("owner == 42", &obj),
+            Err(QueryError::TypeMismatch {
+                field: "owner".to_string(),
+                op: Op::Eq,
+            })
+        );
+    }
+
+    #[test]
+    fn glob_only_applies_to_string_typed_fields() {
+        let obj = test_object();
+        assert_eq!(
+            eval("content_length ~ \"4*\"", &obj),
+            Err(QueryError::TypeMismatch {
+                field: "content_length".to_string(),
+                op: Op::Glob,
+            })
+        );
+        assert_eq!(eval("content_type ~ \"text/*\"", &obj), Ok(true));
+    }
+
+    #[test]
+    fn missing_headers_key_is_false_without_erroring() {
+        let obj = test_object();
+        assert_eq!(eval("headers.nonexistent == \"x\"", &obj), Ok(false));
+    }
+
+    #[test]
+    fn dotted_header_path_resolves_typed_values() {
+        let obj = test_object();
+        assert_eq!(eval("headers.durability-level >= 2", &obj), Ok(true));
+        assert_eq!(eval("headers.m-custom == \"hello\"", &obj), Ok(true));
+    }
+
+    #[test]
+    fn and_or_not_and_parens_compose() {
+        let obj = test_object();
+
+        assert_eq!(
+            eval("(owner == \"poseidon\") AND (content_length > 10)", &obj),
+            Ok(true)
+        );
+        assert_eq!(
+            eval("(owner == \"nobody\") OR (content_length > 10)", &obj),
+            Ok(true)
+        );
+        assert_eq!(eval("NOT (owner == \"nobody\")", &obj), Ok(true));
+        assert_eq!(
+            eval(
+                "NOT (owner == \"poseidon\" AND content_length > 10)",
+                &obj
+            ),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn filter_keeps_only_matching_objects() {
+        let matching = test_object();
+        let mut other = test_object();
+        other.owner = "someone-else".to_string();
+
+        let result = filter(vec![matching.clone(), other], "owner == \"poseidon\"")
+            .unwrap();
+
+        assert_eq!(result, vec![matching]);
+    }
+
+    #[test]
+    fn glob_match_handles_multiple_wildcards() {
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+        assert!(glob_match("*", "anything"));
+        assert!(!glob_match("abc", "abcd"));
+    }
+}
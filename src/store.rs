@@ -0,0 +1,334 @@
+// Copyright 2019 Joyent, Inc.
+
+//! Local SQLite metadata cache for `MantaObject` records.
+//!
+//! This gives tools that need to snapshot and query the Manta namespace a
+//! way to warm-cache directory listings offline instead of round-tripping
+//! every lookup through Moray. Connections are pooled with r2d2 and tuned
+//! with a handful of `PRAGMA`s when the pool opens them; schema management is
+//! handled by a set of embedded diesel migrations.
+
+use crate::migration;
+use crate::moray::MantaObject;
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool, PooledConnection};
+use diesel::sqlite::SqliteConnection;
+use serde_json::Value;
+use std::fmt;
+
+embed_migrations!("migrations");
+
+pub type SqlitePool = Pool<ConnectionManager<SqliteConnection>>;
+pub type SqlitePooledConnection = PooledConnection<ConnectionManager<SqliteConnection>>;
+
+table! {
+    objects (object_id) {
+        object_id -> Text,
+        owner -> Text,
+        dirname -> Text,
+        key -> Text,
+        mtime -> BigInt,
+        data -> Text,
+    }
+}
+
+/// Customizer applied to every connection the pool hands out.
+///
+/// `busy_timeout_ms` controls how long SQLite waits on a lock held by
+/// another connection before giving up with `SQLITE_BUSY`.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionOptions {
+    pub busy_timeout_ms: u32,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            busy_timeout_ms: 5_000,
+        }
+    }
+}
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(
+        &self,
+        conn: &mut SqliteConnection,
+    ) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute(&format!(
+            "PRAGMA busy_timeout = {};
+             PRAGMA journal_mode = WAL;
+             PRAGMA foreign_keys = ON;",
+            self.busy_timeout_ms
+        ))
+        .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Pool(diesel::r2d2::PoolError),
+    Db(diesel::result::Error),
+    Migration(diesel_migrations::RunMigrationsError),
+    Json(serde_json::Error),
+    SchemaVersion(migration::UnknownSchemaVersion),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Pool(e) => write!(f, "connection pool error: {}", e),
+            StoreError::Db(e) => write!(f, "database error: {}", e),
+            StoreError::Migration(e) => write!(f, "migration error: {}", e),
+            StoreError::Json(e) => write!(f, "serialization error: {}", e),
+            StoreError::SchemaVersion(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<diesel::r2d2::PoolError> for StoreError {
+    fn from(e: diesel::r2d2::PoolError) -> Self {
+        StoreError::Pool(e)
+    }
+}
+
+impl From<diesel::result::Error> for StoreError {
+    fn from(e: diesel::result::Error) -> Self {
+        StoreError::Db(e)
+    }
+}
+
+impl From<diesel_migrations::RunMigrationsError> for StoreError {
+    fn from(e: diesel_migrations::RunMigrationsError) -> Self {
+        StoreError::Migration(e)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Json(e)
+    }
+}
+
+impl From<migration::UnknownSchemaVersion> for StoreError {
+    fn from(e: migration::UnknownSchemaVersion) -> Self {
+        StoreError::SchemaVersion(e)
+    }
+}
+
+/// Build a pooled connection to the SQLite database at `database_url`,
+/// tuning every connection the pool opens with `options` and bringing the schema up to
+/// date with the embedded migrations.
+pub fn init_pool(
+    database_url: &str,
+    options: ConnectionOptions,
+) -> Result<SqlitePool, StoreError> {
+    let manager = ConnectionManager::<SqliteConnection>::new(database_url);
+    let pool = Pool::builder()
+        .connection_customizer(Box::new(options))
+        .build(manager)?;
+
+    let conn = pool.get()?;
+    embedded_migrations::run(&conn)?;
+
+    Ok(pool)
+}
+
+/// Insert `obj` into the cache, replacing any existing row for the same
+/// `object_id`. Callers warm-cache a directory listing by re-running this
+/// on every refresh, so re-inserting an already-cached object must update
+/// it in place rather than failing on the `object_id` primary key.
+pub fn insert_object(
+    conn: &SqliteConnection,
+    obj: &MantaObject,
+) -> Result<(), StoreError> {
+    let versioned = migration::stamp_current_version(serde_json::to_value(obj)?);
+    let data = serde_json::to_string(&versioned)?;
+
+    diesel::replace_into(objects::table)
+        .values((
+            objects::object_id.eq(&obj.object_id),
+            objects::owner.eq(&obj.owner),
+            objects::dirname.eq(&obj.dirname),
+            objects::key.eq(&obj.key),
+            objects::mtime.eq(obj.mtime as i64),
+            objects::data.eq(data),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Parse a stored blob, running it through the schema migration chain
+/// before handing it to serde so older rows keep loading correctly.
+fn decode_object(data: &str) -> Result<MantaObject, StoreError> {
+    let value: Value = serde_json::from_str(data)?;
+    let migrated = migration::migrate_to_current(value)?;
+    serde_json::from_value(migrated).map_err(StoreError::from)
+}
+
+pub fn get_by_key(
+    conn: &SqliteConnection,
+    dirname: &str,
+    key: &str,
+) -> Result<Option<MantaObject>, StoreError> {
+    let row: Option<String> = objects::table
+        .filter(objects::dirname.eq(dirname))
+        .filter(objects::key.eq(key))
+        .select(objects::data)
+        .first(conn)
+        .optional()?;
+
+    row.map(|data| decode_object(&data)).transpose()
+}
+
+pub fn list_by_dirname(
+    conn: &SqliteConnection,
+    dirname: &str,
+) -> Result<Vec<MantaObject>, StoreError> {
+    let rows: Vec<String> = objects::table
+        .filter(objects::dirname.eq(dirname))
+        .select(objects::data)
+        .load(conn)?;
+
+    rows.iter().map(|data| decode_object(data)).collect()
+}
+
+pub fn delete(conn: &SqliteConnection, object_id: &str) -> Result<usize, StoreError> {
+    diesel::delete(objects::table.filter(objects::object_id.eq(object_id)))
+        .execute(conn)
+        .map_err(StoreError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::{Arbitrary, StdGen};
+
+    fn test_conn() -> SqliteConnection {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        embedded_migrations::run(&conn).unwrap();
+        conn
+    }
+
+    fn test_object() -> MantaObject {
+        let mut g = StdGen::new(rand::thread_rng(), 16);
+        MantaObject::arbitrary(&mut g)
+    }
+
+    fn temp_db_path(name: &str) -> std::path::PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "rust-libmanta-store-test-{}-{}.db",
+            name,
+            std::process::id()
+        ));
+        path
+    }
+
+    #[test]
+    fn insert_and_get_by_key_round_trips() {
+        let conn = test_conn();
+        let obj = test_object();
+
+        insert_object(&conn, &obj).unwrap();
+
+        assert_eq!(get_by_key(&conn, &obj.dirname, &obj.key).unwrap(), Some(obj));
+    }
+
+    #[test]
+    fn get_by_key_missing_returns_none() {
+        let conn = test_conn();
+        assert_eq!(get_by_key(&conn, "nope", "nope").unwrap(), None);
+    }
+
+    #[test]
+    fn inserting_the_same_object_id_again_refreshes_the_row() {
+        let conn = test_conn();
+        let mut obj = test_object();
+
+        insert_object(&conn, &obj).unwrap();
+
+        obj.content_length += 1;
+        insert_object(&conn, &obj).unwrap();
+
+        assert_eq!(get_by_key(&conn, &obj.dirname, &obj.key).unwrap(), Some(obj));
+    }
+
+    #[test]
+    fn list_by_dirname_returns_only_matching_rows() {
+        let conn = test_conn();
+
+        let mut obj = test_object();
+        obj.dirname = "/poseidon/stor".to_string();
+        obj.object_id = "11111111-1111-1111-1111-111111111111".to_string();
+        insert_object(&conn, &obj).unwrap();
+
+        let mut other = test_object();
+        other.dirname = "/poseidon/stor/elsewhere".to_string();
+        other.object_id = "22222222-2222-2222-2222-222222222222".to_string();
+        insert_object(&conn, &other).unwrap();
+
+        assert_eq!(list_by_dirname(&conn, "/poseidon/stor").unwrap(), vec![obj]);
+    }
+
+    #[test]
+    fn delete_removes_the_row() {
+        let conn = test_conn();
+        let obj = test_object();
+        insert_object(&conn, &obj).unwrap();
+
+        assert_eq!(delete(&conn, &obj.object_id).unwrap(), 1);
+        assert_eq!(get_by_key(&conn, &obj.dirname, &obj.key).unwrap(), None);
+    }
+
+    #[test]
+    fn inserted_rows_are_stamped_with_the_current_schema_version() {
+        let conn = test_conn();
+        let obj = test_object();
+        insert_object(&conn, &obj).unwrap();
+
+        let raw: String = objects::table
+            .filter(objects::object_id.eq(&obj.object_id))
+            .select(objects::data)
+            .first(&conn)
+            .unwrap();
+
+        assert!(raw.contains(&format!(
+            "\"{}\":{}",
+            migration::SCHEMA_VERSION_FIELD,
+            migration::CURRENT_SCHEMA_VERSION
+        )));
+    }
+
+    #[test]
+    fn init_pool_runs_migrations_and_tunes_connections() {
+        let path = temp_db_path("init-pool");
+        let _ = std::fs::remove_file(&path);
+
+        let pool = init_pool(path.to_str().unwrap(), ConnectionOptions::default())
+            .unwrap();
+        let conn = pool.get().unwrap();
+
+        #[derive(QueryableByName)]
+        struct JournalMode {
+            #[sql_type = "diesel::sql_types::Text"]
+            journal_mode: String,
+        }
+        let modes: Vec<JournalMode> = diesel::sql_query("PRAGMA journal_mode")
+            .load(&*conn)
+            .unwrap();
+        assert_eq!(modes[0].journal_mode, "wal");
+
+        let obj = test_object();
+        insert_object(&conn, &obj).unwrap();
+        assert_eq!(get_by_key(&conn, &obj.dirname, &obj.key).unwrap(), Some(obj));
+
+        drop(conn);
+        drop(pool);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,16 @@
+// Copyright 2019 Joyent, Inc.
+
+//! Small helpers shared by the `Arbitrary` impls in this crate's `moray`
+//! module.
+
+use rand::Rng;
+
+const CHARSET: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Generate a random alphanumeric string of length `len` using `g`.
+pub fn random_string<R: Rng>(g: &mut R, len: usize) -> String {
+    (0..len)
+        .map(|_| CHARSET[g.gen_range(0, CHARSET.len())] as char)
+        .collect()
+}